@@ -1,8 +1,11 @@
 use std::any::{Any, TypeId};
-use std::cell::{RefCell, RefMut};
+use std::cell::{Cell, RefCell, RefMut};
 use std::collections::HashMap;
+use std::marker::PhantomData;
 use std::mem;
 
+use super::{Entity, Error};
+
 pub struct ComponentStorage<T> {
     components: Vec<Option<T>>,
 }
@@ -42,23 +45,17 @@ impl<T> ComponentStorage<T> {
 }
 
 pub trait GenericComponentStorage {
-    fn next_entry(&self, start: usize) -> Option<usize>;
-    fn remove(&mut self, id: usize);
+    /// Removes the entity's component, if any, returning it boxed as `Any` so a caller that only
+    /// knows the component's `TypeId` (like `World::remove_entity`) can still pass it to a
+    /// lifecycle hook before it's dropped.
+    fn remove(&mut self, id: usize) -> Option<Box<Any>>;
     fn as_any(&self) -> &Any;
     fn as_any_mut(&mut self) -> &mut Any;
 }
 
 impl<T: 'static> GenericComponentStorage for ComponentStorage<T> {
-    fn next_entry(&self, start: usize) -> Option<usize> {
-        self.components.get(start..).and_then(|s| {
-            s.iter()
-                .enumerate()
-                .filter_map(|(e, c)| if c.is_some() { Some(start + e) } else { None })
-                .next()
-        })
-    }
-    fn remove(&mut self, id: usize) {
-        self.remove(id);
+    fn remove(&mut self, id: usize) -> Option<Box<Any>> {
+        self.remove(id).map(|c| Box::new(c) as Box<Any>)
     }
     fn as_any(&self) -> &Any {
         self
@@ -68,91 +65,316 @@ impl<T: 'static> GenericComponentStorage for ComponentStorage<T> {
     }
 }
 
+/// Lifecycle hooks fired when a component of type `C` is inserted or removed.
+///
+/// Register with `World::register_component_with_hooks`. A hook is only handed the `Entity` and a
+/// reference to the affected component's value, not a `World` handle, so reacting to anything
+/// beyond that has to go through state the closure captures externally (e.g. an `Rc<RefCell<_>>`
+/// shared with the caller). Hooks do run after the component's own storage borrow has been
+/// released, so a captured handle that calls back into the `World` is free to touch any other
+/// part of it (including other component types) — but not insert or remove its own component
+/// type, since that type's hooks stay mutably borrowed while one of them runs and would be
+/// borrowed again.
+pub struct Hooks<C> {
+    pub on_insert: Option<Box<FnMut(Entity, &C)>>,
+    pub on_remove: Option<Box<FnMut(Entity, &C)>>,
+}
+
+impl<C> Default for Hooks<C> {
+    fn default() -> Hooks<C> {
+        Hooks {
+            on_insert: None,
+            on_remove: None,
+        }
+    }
+}
+
+/// Type-erased form of `Hooks<C>`.
+///
+/// `World` stores one of these per hooked component type, downcasting the `&Any` argument back
+/// to `&C` before invoking the user's callback, so it can keep the hook map free of a generic
+/// parameter.
+pub(crate) struct ErasedHooks {
+    on_insert: Option<Box<FnMut(Entity, &Any)>>,
+    on_remove: Option<Box<FnMut(Entity, &Any)>>,
+}
+
+impl ErasedHooks {
+    pub(crate) fn new<C: 'static>(hooks: Hooks<C>) -> ErasedHooks {
+        ErasedHooks {
+            on_insert: hooks.on_insert.map(|mut f| {
+                Box::new(move |e: Entity, c: &Any| f(e, c.downcast_ref::<C>().unwrap()))
+                    as Box<FnMut(Entity, &Any)>
+            }),
+            on_remove: hooks.on_remove.map(|mut f| {
+                Box::new(move |e: Entity, c: &Any| f(e, c.downcast_ref::<C>().unwrap()))
+                    as Box<FnMut(Entity, &Any)>
+            }),
+        }
+    }
+
+    pub(crate) fn fire_on_insert(&mut self, e: Entity, c: &Any) {
+        if let Some(f) = self.on_insert.as_mut() {
+            f(e, c);
+        }
+    }
+
+    pub(crate) fn fire_on_remove(&mut self, e: Entity, c: &Any) {
+        if let Some(f) = self.on_remove.as_mut() {
+            f(e, c);
+        }
+    }
+}
+
+/// Reserved signature bit marking an entity slot as alive.
+///
+/// Set whenever `World::add_entity` (re)allocates a slot and cleared along with every component
+/// bit by `World::remove_entity`. Folded into every query's required mask so that a query made
+/// entirely of `Without`/`Opt` terms (required mask `0`) still excludes dead slots instead of
+/// matching every index, since a dead and a component-less alive entity both otherwise present a
+/// signature of `0`.
+pub(crate) const ALIVE_BIT: u64 = 1 << 63;
+
 pub trait ComponentSet<'a> {
     type Refs;
 
+    /// Iterates entities whose bitmask signature carries every required component's bit,
+    /// pulling each term's contribution out of its storage by entity index.
     fn iter(
         storage: &'a HashMap<TypeId, RefCell<Box<GenericComponentStorage>>>,
+        bits: &HashMap<TypeId, u32>,
+        signatures: &'a [Cell<u64>],
     ) -> Box<Iterator<Item = (usize, Self::Refs)> + 'a>;
+
+    /// Like `iter`, but attempts to `try_borrow_mut` each involved storage instead of unwrapping
+    /// the borrow, returning `Error::ComponentLocked` instead of panicking if any storage is
+    /// already locked elsewhere. Storages locked before the failing one are released.
+    fn try_iter(
+        storage: &'a HashMap<TypeId, RefCell<Box<GenericComponentStorage>>>,
+        bits: &HashMap<TypeId, u32>,
+        signatures: &'a [Cell<u64>],
+    ) -> Result<Box<Iterator<Item = (usize, Self::Refs)> + 'a>, Error>;
+}
+
+/// Excludes entities that have component `C` from a query.
+///
+/// Used as an element of a `ComponentSet` tuple, e.g. `world.iter::<(&mut Position, Without<Frozen>)>()`
+/// iterates entities that have `Position` but not `Frozen`. Contributes no reference to the
+/// yielded tuple.
+pub struct Without<C>(PhantomData<C>);
+
+/// Includes component `C` if present, without requiring it.
+///
+/// Used as an element of a `ComponentSet` tuple, e.g. `world.iter::<(&mut Position, Opt<Velocity>)>()`
+/// yields `(&mut Position, Option<&mut Velocity>)` for every entity that has `Position`,
+/// regardless of whether it has `Velocity`.
+pub struct Opt<C>(PhantomData<C>);
+
+/// The per-element behavior of a single slot in a `ComponentSet` tuple.
+///
+/// Implemented for `&'a mut C` (required, drives entity selection), `Without<C>` (excludes
+/// entities that have `C`), and `Opt<C>` (includes `C` if present). This lets
+/// `implement_tuple_set!` treat all three uniformly in its join loop.
+///
+/// Required components are written as `&mut C` rather than bare `C` so that `Without<C>` and
+/// `Opt<C>` can be implemented for this trait alongside them without conflicting: `&mut C`,
+/// `Without<C>` and `Opt<C>` are distinct types, whereas a blanket impl for bare `C` would
+/// overlap with the `Without`/`Opt` impls.
+pub trait QueryTerm<'a> {
+    type Component: 'static;
+    type Out;
+
+    /// Whether this term must be present for an entity to match. Required terms contribute their
+    /// component's bit to the query's required mask; `Without` and `Opt` never do, and are only
+    /// checked against entities that already carry the required mask.
+    const REQUIRED: bool;
+
+    /// Whether the entity at `index` passes this term's filter.
+    ///
+    /// Always true except for `Without<C>`, which rejects entities that have `C`.
+    fn accepts(storage: &ComponentStorage<Self::Component>, index: usize) -> bool;
+
+    /// Produces this term's contribution to the tuple for the given entity index.
+    unsafe fn get(storage: &mut ComponentStorage<Self::Component>, index: usize) -> Self::Out;
+}
+
+impl<'a, C: 'static> QueryTerm<'a> for &'a mut C {
+    type Component = C;
+    type Out = &'a mut C;
+
+    const REQUIRED: bool = true;
+
+    fn accepts(_storage: &ComponentStorage<C>, _index: usize) -> bool {
+        true
+    }
+
+    unsafe fn get(storage: &mut ComponentStorage<C>, index: usize) -> Self::Out {
+        mem::transmute::<&mut C, &'a mut C>(storage.get_mut(index).unwrap())
+    }
 }
 
-macro_rules! replace_expr {
-    ($_t:tt $sub:expr) => {
-        $sub
-    };
+impl<'a, C: 'static> QueryTerm<'a> for Without<C> {
+    type Component = C;
+    type Out = ();
+
+    const REQUIRED: bool = false;
+
+    fn accepts(storage: &ComponentStorage<C>, index: usize) -> bool {
+        !storage.contains(index)
+    }
+
+    unsafe fn get(_storage: &mut ComponentStorage<C>, _index: usize) -> Self::Out {}
+}
+
+impl<'a, C: 'static> QueryTerm<'a> for Opt<C> {
+    type Component = C;
+    type Out = Option<&'a mut C>;
+
+    const REQUIRED: bool = false;
+
+    fn accepts(_storage: &ComponentStorage<C>, _index: usize) -> bool {
+        true
+    }
+
+    unsafe fn get(storage: &mut ComponentStorage<C>, index: usize) -> Self::Out {
+        mem::transmute::<Option<&mut C>, Option<&'a mut C>>(storage.get_mut(index))
+    }
 }
 
 macro_rules! implement_tuple_set {
     ($($x:ident:$xn:ident),*) => {
-        impl<'a, $($x: 'static,)*> ComponentSet<'a> for ($($x,)*) {
-            type Refs = ($(&'a mut $x,)*);
+        impl<'a, $($x: QueryTerm<'a> + 'a,)*> ComponentSet<'a> for ($($x,)*) {
+            type Refs = ($($x::Out,)*);
 
             fn iter(
-                storage: &'a HashMap<TypeId, RefCell<Box<GenericComponentStorage>>>
+                storage: &'a HashMap<TypeId, RefCell<Box<GenericComponentStorage>>>,
+                bits: &HashMap<TypeId, u32>,
+                signatures: &'a [Cell<u64>],
             ) -> Box<Iterator<Item = (usize, Self::Refs)> + 'a> {
-
-                struct ComponentIterator<'a, $($x: 'a),*> {
+                struct ComponentIterator<'a, $($x: QueryTerm<'a> + 'a),*> {
                     index: usize,
-                    $($xn: (RefMut<'a, ComponentStorage<$x>>)),*
+                    required_mask: u64,
+                    signatures: &'a [Cell<u64>],
+                    $($xn: (RefMut<'a, ComponentStorage<$x::Component>>)),*
                 }
-                impl<'a, $($x: 'static),*> Iterator for ComponentIterator<'a, $($x),*> {
-                    type Item = (usize, ($(&'a mut $x,)*));
+                impl<'a, $($x: QueryTerm<'a> + 'a),*> Iterator for ComponentIterator<'a, $($x),*> {
+                    type Item = (usize, ($($x::Out,)*));
 
                     fn next(&mut self) -> Option<Self::Item> {
-                        let component_count = 0 $(+ replace_expr!($x 1))*;
-                        let mut entity = self.index;
-                        let mut entity_count = 0;
-                        let next_entity = loop {
-                            $(
-                                if let Some(e) = self.$xn.next_entry(entity) {
-                                    if e != entity {
-                                        entity_count = 0;
-                                    }
-                                    entity_count += 1;
-                                    entity = e;
-                                } else {
-                                    break None;
-                                }
-
-                                if entity_count == component_count {
-                                    break Some(entity);
-                                }
-                            )*
-                            entity += 1;
-                        };
-
-                        if let Some(e) = next_entity {
-                            self.index = e + 1;
+                        while self.index < self.signatures.len() {
+                            let entity = self.index;
+                            self.index += 1;
+
+                            if self.signatures[entity].get() & self.required_mask != self.required_mask {
+                                continue;
+                            }
+
+                            // Without/Opt terms never contribute to the required mask, so check them
+                            // against the entity separately; Without rejects it here if needed.
+                            let accepted = true $(&& $x::accepts(&self.$xn, entity))*;
+                            if !accepted {
+                                continue;
+                            }
 
                             // we can transmute the lifetime of the references to the lifetime of the iterator because:
                             // * this iterator holds a mutable reference to the component storage, guaranteeing there are no
                             //   other references to the storage or any component entry in the storage
                             // * the iterator can return only one mutable reference to each unique component entry
                             unsafe {
-                                Some((
-                                    e,
-                                    (
-                                        $(mem::transmute::<&mut $x, &'a mut $x>(self.$xn.get_mut(e).unwrap()),)+
-                                    )
-                                ))
+                                return Some((
+                                    entity,
+                                    ($($x::get(&mut self.$xn, entity),)*)
+                                ));
                             }
-                        } else {
-                            None
                         }
-
+                        None
                     }
                 }
 
+                let required_mask: u64 = ALIVE_BIT $(| if $x::REQUIRED {
+                    1u64 << bits.get(&TypeId::of::<$x::Component>()).expect("component not registered")
+                } else {
+                    0
+                })*;
+
                 Box::new(
-                    ComponentIterator {
+                    ComponentIterator::<$($x),*> {
                         index: 0,
+                        required_mask,
+                        signatures,
                         $($xn: RefMut::map(
-                            storage.get(&TypeId::of::<$x>()).expect("component not registered").borrow_mut(),
-                            |s| s.as_any_mut().downcast_mut::<ComponentStorage<$x>>().unwrap()
+                            storage.get(&TypeId::of::<$x::Component>()).expect("component not registered").borrow_mut(),
+                            |s| s.as_any_mut().downcast_mut::<ComponentStorage<$x::Component>>().unwrap()
                         )),*
                     }
                 )
             }
+
+            fn try_iter(
+                storage: &'a HashMap<TypeId, RefCell<Box<GenericComponentStorage>>>,
+                bits: &HashMap<TypeId, u32>,
+                signatures: &'a [Cell<u64>],
+            ) -> Result<Box<Iterator<Item = (usize, Self::Refs)> + 'a>, Error> {
+                struct ComponentIterator<'a, $($x: QueryTerm<'a> + 'a),*> {
+                    index: usize,
+                    required_mask: u64,
+                    signatures: &'a [Cell<u64>],
+                    $($xn: (RefMut<'a, ComponentStorage<$x::Component>>)),*
+                }
+                impl<'a, $($x: QueryTerm<'a> + 'a),*> Iterator for ComponentIterator<'a, $($x),*> {
+                    type Item = (usize, ($($x::Out,)*));
+
+                    fn next(&mut self) -> Option<Self::Item> {
+                        while self.index < self.signatures.len() {
+                            let entity = self.index;
+                            self.index += 1;
+
+                            if self.signatures[entity].get() & self.required_mask != self.required_mask {
+                                continue;
+                            }
+
+                            // Without/Opt terms never contribute to the required mask, so check them
+                            // against the entity separately; Without rejects it here if needed.
+                            let accepted = true $(&& $x::accepts(&self.$xn, entity))*;
+                            if !accepted {
+                                continue;
+                            }
+
+                            // we can transmute the lifetime of the references to the lifetime of the iterator because:
+                            // * this iterator holds a mutable reference to the component storage, guaranteeing there are no
+                            //   other references to the storage or any component entry in the storage
+                            // * the iterator can return only one mutable reference to each unique component entry
+                            unsafe {
+                                return Some((
+                                    entity,
+                                    ($($x::get(&mut self.$xn, entity),)*)
+                                ));
+                            }
+                        }
+                        None
+                    }
+                }
+
+                let required_mask: u64 = ALIVE_BIT $(| if $x::REQUIRED {
+                    1u64 << bits.get(&TypeId::of::<$x::Component>()).expect("component not registered")
+                } else {
+                    0
+                })*;
+
+                Ok(Box::new(
+                    ComponentIterator::<$($x),*> {
+                        index: 0,
+                        required_mask,
+                        signatures,
+                        $($xn: RefMut::map(
+                            storage.get(&TypeId::of::<$x::Component>()).expect("component not registered")
+                                .try_borrow_mut()
+                                .map_err(|_| Error::ComponentLocked(TypeId::of::<$x::Component>()))?,
+                            |s| s.as_any_mut().downcast_mut::<ComponentStorage<$x::Component>>().unwrap()
+                        )),*
+                    }
+                ))
+            }
         }
     }
 }
@@ -21,14 +21,15 @@
 //!
 //! lil-ecs will also panic if any attempt is made to insert, remove, or iterate on an unregistered component
 
-use std::any::TypeId;
-use std::cell::{Ref, RefCell, RefMut};
+use std::any::{Any, TypeId};
+use std::cell::{Cell, Ref, RefCell, RefMut};
 use std::collections::HashMap;
 use std::fmt;
 
 mod component;
 
-use component::{ComponentSet, ComponentStorage, GenericComponentStorage};
+use component::{ComponentSet, ComponentStorage, ErasedHooks, GenericComponentStorage, ALIVE_BIT};
+pub use component::{Hooks, Opt, Without};
 
 /// Error type of lil-ecs.
 ///
@@ -44,6 +45,12 @@ pub enum Error {
     ///
     /// An error triggered on trying to read, insert, or remove a component that has not been registered in the World.
     UnregisteredComponentAccess,
+
+    /// The component is already locked elsewhere.
+    ///
+    /// Error triggered by the `try_*` family of methods when a component's storage is already
+    /// mutably or immutably borrowed, instead of panicking like their non-fallible counterparts.
+    ComponentLocked(TypeId),
 }
 
 impl fmt::Display for Error {
@@ -53,6 +60,9 @@ impl fmt::Display for Error {
             Error::UnregisteredComponentAccess => {
                 write!(f, "Attempt to access unregistered component")
             }
+            Error::ComponentLocked(type_id) => {
+                write!(f, "Component '{:?}' is already locked elsewhere", type_id)
+            }
         }
     }
 }
@@ -79,6 +89,12 @@ pub struct EntityEntry<'a> {
     e: Entity,
 }
 
+impl<'a> fmt::Debug for EntityEntry<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EntityEntry").field("e", &self.e).finish()
+    }
+}
+
 impl<'a> EntityEntry<'a> {
     pub fn entity(&self) -> Entity {
         self.e
@@ -104,6 +120,48 @@ impl<'a> EntityEntry<'a> {
     }
 }
 
+/// Lets `World::entities` accept a single `Entity`, a `[Entity; N]` array, or a `&[Entity]` slice
+/// and return the correspondingly-shaped `EntityEntry` result.
+///
+/// Implemented for `Entity`, `&'a [Entity]`, and `[Entity; N]` for `N` from 1 to 4.
+pub trait EntityQuery<'a> {
+    type Output;
+
+    fn query(self, world: &'a World) -> Result<Self::Output, Error>;
+}
+
+impl<'a> EntityQuery<'a> for Entity {
+    type Output = EntityEntry<'a>;
+
+    fn query(self, world: &'a World) -> Result<EntityEntry<'a>, Error> {
+        world.entity(self)
+    }
+}
+
+impl<'a> EntityQuery<'a> for &'a [Entity] {
+    type Output = Vec<EntityEntry<'a>>;
+
+    fn query(self, world: &'a World) -> Result<Vec<EntityEntry<'a>>, Error> {
+        self.iter().map(|&e| world.entity(e)).collect()
+    }
+}
+
+macro_rules! implement_entity_array_query {
+    ($n:expr, $($i:tt),*) => {
+        impl<'a> EntityQuery<'a> for [Entity; $n] {
+            type Output = [EntityEntry<'a>; $n];
+
+            fn query(self, world: &'a World) -> Result<[EntityEntry<'a>; $n], Error> {
+                Ok([$(world.entity(self[$i])?,)*])
+            }
+        }
+    }
+}
+implement_entity_array_query! {1, 0}
+implement_entity_array_query! {2, 0, 1}
+implement_entity_array_query! {3, 0, 1, 2}
+implement_entity_array_query! {4, 0, 1, 2, 3}
+
 /// A collection of entities and components
 ///
 ///
@@ -132,14 +190,14 @@ impl<'a> EntityEntry<'a> {
 ///     .entity();
 /// let e3 = world.add_entity().insert(Position(0, 0)).unwrap().entity();
 ///
-/// for (entity, (mut pos,)) in world.iter_entities::<(Position,)>() {
+/// for (entity, (mut pos,)) in world.iter_entities::<(&mut Position,)>() {
 ///     pos.0 = 10;
 ///     if entity == e1 {
 ///         pos.0 = 0;
 ///     }
 /// }
 ///
-/// for (entity, (mut pos, vel)) in world.iter_entities::<(Position, Velocity)>() {
+/// for (entity, (mut pos, vel)) in world.iter_entities::<(&mut Position, &mut Velocity)>() {
 ///     pos.0 += vel.0;
 ///     pos.1 += vel.1;
 ///     if entity == e1 {
@@ -147,7 +205,7 @@ impl<'a> EntityEntry<'a> {
 ///     }
 /// }
 ///
-/// for (mut pos,) in world.iter::<(Position,)>() {
+/// for (mut pos,) in world.iter::<(&mut Position,)>() {
 ///     pos.0 = 5;
 /// }
 ///
@@ -157,46 +215,137 @@ impl<'a> EntityEntry<'a> {
 /// ```
 pub struct World {
     components: HashMap<TypeId, RefCell<Box<GenericComponentStorage>>>,
+    resources: HashMap<TypeId, RefCell<Box<Any>>>,
     entities: Vec<u32>,
     dead: Vec<usize>,
+    /// Bit index assigned to each registered component type, used to build per-entity signatures.
+    component_bits: HashMap<TypeId, u32>,
+    next_bit: u32,
+    /// Per-entity bitmask of which registered components that entity currently has, indexed by
+    /// entity index alongside `entities`. Lets queries skip straight to matching entities instead
+    /// of walking each storage's occupied slots.
+    signatures: Vec<Cell<u64>>,
+    hooks: HashMap<TypeId, RefCell<ErasedHooks>>,
 }
 
 impl World {
     pub fn new() -> World {
         World {
             components: HashMap::new(),
+            resources: HashMap::new(),
             entities: Vec::new(),
             dead: Vec::new(),
+            component_bits: HashMap::new(),
+            next_bit: 0,
+            signatures: Vec::new(),
+            hooks: HashMap::new(),
         }
     }
 
     /// Registers a component for use in the world.
     ///
     /// Any type can be registered as a component. Components are indexed by type, meaning each component must to be a *unique* type.
+    ///
+    /// # Panics
+    /// Panics if more than 63 component types are registered: signatures are packed into a single
+    /// `u64`, and bit 63 is reserved to mark an entity slot as alive.
     pub fn register_component<C: 'static>(&mut self) {
+        assert!(
+            self.next_bit < 63,
+            "lil-ecs supports at most 63 registered component types"
+        );
+        self.component_bits.insert(TypeId::of::<C>(), self.next_bit);
+        self.next_bit += 1;
         self.components.insert(
             TypeId::of::<C>(),
             RefCell::new(Box::new(ComponentStorage::<C>::new())),
         );
     }
 
+    /// Registers a component for use in the world, along with hooks fired whenever an instance
+    /// of it is inserted or removed.
+    ///
+    /// See `Hooks` for details on when each hook runs.
+    ///
+    /// # Panics
+    /// Panics if more than 63 component types are registered: signatures are packed into a single
+    /// `u64`, and bit 63 is reserved to mark an entity slot as alive.
+    pub fn register_component_with_hooks<C: 'static>(&mut self, hooks: Hooks<C>) {
+        self.register_component::<C>();
+        self.hooks
+            .insert(TypeId::of::<C>(), RefCell::new(ErasedHooks::new(hooks)));
+    }
+
+    /// Inserts a resource into the world, replacing any existing resource of the same type.
+    ///
+    /// Resources are singleton values indexed by type, much like components, but are not tied to
+    /// any entity. Useful for things like a delta-time clock, an RNG, or an input snapshot.
+    pub fn insert_resource<R: 'static>(&mut self, r: R) {
+        self.resources
+            .insert(TypeId::of::<R>(), RefCell::new(Box::new(r)));
+    }
+
+    /// Gets a resource of the given type.
+    ///
+    /// Returns `None` if no resource of that type has been inserted.
+    ///
+    /// # Panics
+    /// Panics if the resource is already locked elsewhere.
+    pub fn resource<R: 'static>(&self) -> Option<Ref<R>> {
+        self.resources
+            .get(&TypeId::of::<R>())
+            .map(|r| Ref::map(r.borrow(), |r| r.downcast_ref::<R>().unwrap()))
+    }
+
+    /// Mutably gets a resource of the given type.
+    ///
+    /// Returns `None` if no resource of that type has been inserted.
+    ///
+    /// # Panics
+    /// Panics if the resource is already locked elsewhere.
+    pub fn resource_mut<R: 'static>(&self) -> Option<RefMut<R>> {
+        self.resources
+            .get(&TypeId::of::<R>())
+            .map(|r| RefMut::map(r.borrow_mut(), |r| r.downcast_mut::<R>().unwrap()))
+    }
+
     /// Gets an EntityEntry for the provided Entity
-    pub fn entity<'a>(&'a mut self, e: Entity) -> Option<EntityEntry<'a>> {
+    ///
+    /// Returns `Error::DeadEntityAccess` if the entity is not alive.
+    pub fn entity<'a>(&'a self, e: Entity) -> Result<EntityEntry<'a>, Error> {
+        self.check_alive(e)?;
+        Ok(EntityEntry { world: self, e: e })
+    }
+
+    /// Gets EntityEntries for a batch of entities at once.
+    ///
+    /// Accepts a single `Entity`, a `[Entity; N]` array (`N` up to 4), or a `&[Entity]` slice,
+    /// returning an `EntityEntry`, `[EntityEntry; N]`, or `Vec<EntityEntry>` respectively.
+    /// Short-circuits to `Error::DeadEntityAccess` on the first entity that isn't alive.
+    pub fn entities<'a, Q: EntityQuery<'a>>(&'a self, q: Q) -> Result<Q::Output, Error> {
+        q.query(self)
+    }
+
+    /// Checks that `e` refers to a currently-alive entity.
+    fn check_alive(&self, e: Entity) -> Result<(), Error> {
         match self.entities.get(e.index) {
-            Some(&gen) if gen == e.generation => Some(EntityEntry { world: self, e: e }),
-            _ => None,
+            Some(&gen) if gen == e.generation => Ok(()),
+            _ => Err(Error::DeadEntityAccess(e)),
         }
     }
 
     /// Allocates a new Entity and returns an EntityEntry for the newly created Entity
     pub fn add_entity<'a>(&'a mut self) -> EntityEntry<'a> {
         let entity_id = if let Some(index) = self.dead.pop() {
-            let generation = self.entities[index] + 1;
-            self.entities[index] = generation;
+            // The generation was already bumped by `remove_entity` when this slot was freed, so
+            // reusing it here doesn't need to bump it again.
+            let generation = self.entities[index];
+            self.signatures[index].set(ALIVE_BIT);
             Entity { index, generation }
         } else {
             let index = self.entities.len();
             self.entities.push(0);
+            self.signatures.push(Cell::new(ALIVE_BIT));
             Entity {
                 index,
                 generation: 0,
@@ -210,14 +359,20 @@ impl World {
 
     /// Removes an Entity
     pub fn remove_entity(&mut self, e: Entity) {
-        match self.entities.get(e.index) {
-            Some(&gen) if gen == e.generation => {
-                for (_, storage) in self.components.iter() {
-                    storage.borrow_mut().remove(e.index);
+        if self.check_alive(e).is_ok() {
+            for (type_id, storage) in self.components.iter() {
+                let removed = storage.borrow_mut().remove(e.index);
+                if let Some(c) = removed {
+                    if let Some(hooks) = self.hooks.get(type_id) {
+                        hooks.borrow_mut().fire_on_remove(e, &*c);
+                    }
                 }
-                self.dead.push(e.index);
             }
-            _ => {}
+            // Bump the generation immediately so every outstanding `Entity` handle is invalidated
+            // right away, rather than only once the slot happens to be reused.
+            self.entities[e.index] += 1;
+            self.signatures[e.index].set(0);
+            self.dead.push(e.index);
         }
     }
 
@@ -243,6 +398,41 @@ impl World {
         }
     }
 
+    /// Gets a specific component for a batch of entities at once.
+    ///
+    /// Entities that are dead, don't have the component, or whose component type isn't
+    /// registered at all map to `None`, so code that relates pairs of entities (collision
+    /// responses, parent/child transforms) can fetch both sides without handling
+    /// `Error::DeadEntityAccess` or `Error::UnregisteredComponentAccess` for each one
+    /// individually.
+    ///
+    /// # Panics
+    /// Panics if the component is already locked elsewhere.
+    pub fn get_components<'b, C: 'static>(&'b self, entities: &[Entity]) -> Vec<Option<Ref<'b, C>>> {
+        entities
+            .iter()
+            .map(|&e| self.get_component::<C>(e).ok().and_then(|c| c))
+            .collect()
+    }
+
+    /// Mutably gets a specific component from an entity without panicking on a locked storage
+    ///
+    /// Returns an Error::DeadEntityAccess error if the entity is not alive, or an
+    /// Error::ComponentLocked error if the component's storage is already borrowed elsewhere.
+    /// Successful result is Some(RefMut<C>) if the entity has the specified component, otherwise None
+    pub fn try_get_component_mut<'b, C: 'static>(
+        &'b self,
+        e: Entity,
+    ) -> Result<Option<RefMut<'b, C>>, Error> {
+        self.check_alive(e)?;
+        let storage = self.try_get_storage_mut::<C>()?;
+        if storage.contains(e.index) {
+            Ok(Some(RefMut::map(storage, |s| s.get_mut(e.index).unwrap())))
+        } else {
+            Ok(None)
+        }
+    }
+
     /// Iterates over a ComponentSet
     ///
     /// ComponentSet is implemented for all tuples from `(A,)` to `(A, B, C, D, E, F, G, H, I, J, K, L)`
@@ -251,8 +441,27 @@ impl World {
     ///
     /// # Panics
     /// Panics if any of the components are not registered, or if any of the components are locked elsewhere.
-    pub fn iter<'a, C: ComponentSet<'a>>(&'a self) -> Box<Iterator<Item = C::IterItem> + 'a> {
-        C::iter(&self.components)
+    pub fn iter<'a, C: ComponentSet<'a> + 'a>(&'a self) -> Box<Iterator<Item = C::Refs> + 'a> {
+        Box::new(
+            C::iter(&self.components, &self.component_bits, &self.signatures).map(|(_, cs)| cs),
+        )
+    }
+
+    /// Iterates over a ComponentSet without panicking on a locked storage
+    ///
+    /// ComponentSet is implemented for all tuples from `(A,)` to `(A, B, C, D, E, F, G, H, I, J, K, L)`
+    ///
+    /// Mutably locks the storage for each component included in the component set. Returns an
+    /// Error::ComponentLocked error instead of panicking if any of the involved storages are
+    /// already locked elsewhere; any storage successfully locked before the failure is released.
+    ///
+    /// # Panics
+    /// Panics if any of the components are not registered.
+    pub fn try_iter<'a, C: ComponentSet<'a> + 'a>(
+        &'a self,
+    ) -> Result<Box<Iterator<Item = C::Refs> + 'a>, Error> {
+        let iter = C::try_iter(&self.components, &self.component_bits, &self.signatures)?;
+        Ok(Box::new(iter.map(|(_, cs)| cs)))
     }
 
     /// Iterates over a ComponentSet and also provides the Entity for which the components belong
@@ -263,11 +472,12 @@ impl World {
     ///
     /// # Panics
     /// Panics if any of the components are not registered, or if any of the components are locked elsewhere.
-    pub fn iter_entities<'a, C: ComponentSet<'a> + 'static>(
+    pub fn iter_entities<'a, C: ComponentSet<'a> + 'a>(
         &'a self,
-    ) -> Box<Iterator<Item = (Entity, C::IterItem)> + 'a> {
+    ) -> Box<Iterator<Item = (Entity, C::Refs)> + 'a> {
         let entities = &self.entities;
-        Box::new(C::indexed(&self.components).map(move |(e, cs)| {
+        let iter = C::iter(&self.components, &self.component_bits, &self.signatures);
+        Box::new(iter.map(move |(e, cs)| {
             (
                 Entity {
                     index: e,
@@ -278,6 +488,33 @@ impl World {
         }))
     }
 
+    /// Iterates over a ComponentSet and also provides the Entity for which the components belong,
+    /// without panicking on a locked storage
+    ///
+    /// ComponentSet is implemented for all tuples from `(A,)` to `(A, B, C, D, E, F, G, H, I, J, K, L)`
+    ///
+    /// Mutably locks the storage for each component included in the component set. Returns an
+    /// Error::ComponentLocked error instead of panicking if any of the involved storages are
+    /// already locked elsewhere; any storage successfully locked before the failure is released.
+    ///
+    /// # Panics
+    /// Panics if any of the components are not registered.
+    pub fn try_iter_entities<'a, C: ComponentSet<'a> + 'a>(
+        &'a self,
+    ) -> Result<Box<Iterator<Item = (Entity, C::Refs)> + 'a>, Error> {
+        let entities = &self.entities;
+        let iter = C::try_iter(&self.components, &self.component_bits, &self.signatures)?;
+        Ok(Box::new(iter.map(move |(e, cs)| {
+            (
+                Entity {
+                    index: e,
+                    generation: entities[e],
+                },
+                cs,
+            )
+        })))
+    }
+
     fn get_storage<T: 'static>(&self) -> Result<Ref<ComponentStorage<T>>, Error> {
         Ok(Ref::map(
             self.components
@@ -302,27 +539,71 @@ impl World {
         ))
     }
 
+    fn try_get_storage_mut<T: 'static>(&self) -> Result<RefMut<ComponentStorage<T>>, Error> {
+        let cell = self
+            .components
+            .get(&TypeId::of::<T>())
+            .ok_or(Error::UnregisteredComponentAccess)?;
+        let storage = cell
+            .try_borrow_mut()
+            .map_err(|_| Error::ComponentLocked(TypeId::of::<T>()))?;
+        Ok(RefMut::map(storage, |s| {
+            s.as_any_mut()
+                .downcast_mut::<ComponentStorage<T>>()
+                .unwrap()
+        }))
+    }
+
     fn insert_component<T: 'static>(&self, e: Entity, c: T) -> Result<(), Error> {
-        if e.index >= self.entities.len() || e.generation != self.entities[e.index] {
-            return Err(Error::DeadEntityAccess(e));
-        }
+        self.check_alive(e)?;
         self.get_storage_mut::<T>()?.insert(e.index, c);
+        let bit = *self
+            .component_bits
+            .get(&TypeId::of::<T>())
+            .expect("component not registered");
+        self.signatures[e.index].set(self.signatures[e.index].get() | (1 << bit));
+
+        if let Some(hooks) = self.hooks.get(&TypeId::of::<T>()) {
+            let storage = self.get_storage::<T>()?;
+            let c: *const T = storage.get(e.index).unwrap();
+            // Drop the storage borrow before firing, as documented on `Hooks`: the hook must be
+            // free to read or write any other part of the `World`. Safe to dereference afterwards
+            // because hooks are documented not to insert/remove their own component type, so
+            // nothing can move or drop this entry while the hook runs.
+            drop(storage);
+            hooks.borrow_mut().fire_on_insert(e, unsafe { &*c });
+        }
         Ok(())
     }
 
     fn remove_component<T: 'static>(&self, e: Entity) -> Result<Option<T>, Error> {
-        if e.index >= self.entities.len() || e.generation != self.entities[e.index] {
-            return Err(Error::DeadEntityAccess(e));
+        self.check_alive(e)?;
+        let removed = self.get_storage_mut::<T>()?.remove(e.index);
+        if let Some(ref c) = removed {
+            let bit = *self
+                .component_bits
+                .get(&TypeId::of::<T>())
+                .expect("component not registered");
+            self.signatures[e.index].set(self.signatures[e.index].get() & !(1 << bit));
+
+            if let Some(hooks) = self.hooks.get(&TypeId::of::<T>()) {
+                hooks.borrow_mut().fire_on_remove(e, c);
+            }
         }
-        Ok(self.get_storage_mut::<T>()?.remove(e.index))
+        Ok(removed)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::World;
+    use super::{Error, Hooks, Opt, Without, World};
+    use std::any::TypeId;
+    use std::cell::RefCell;
+    use std::rc::Rc;
 
+    #[derive(Debug)]
     struct Position(i32, i32);
+    #[derive(Debug)]
     struct Velocity(i32, i32);
 
     #[test]
@@ -349,13 +630,13 @@ mod tests {
         let e3 = world.add_entity().insert(Position(0, 0)).unwrap().entity();
 
         let position_entities = &[e1, e2, e3];
-        for (entity, (mut pos,)) in world.iter_entities::<(Position,)>() {
+        for (entity, (mut pos,)) in world.iter_entities::<(&mut Position,)>() {
             pos.0 = 10;
             assert!(position_entities.contains(&entity));
         }
 
         let velocity_entities = &[e1, e2];
-        for (entity, (mut pos, vel)) in world.iter_entities::<(Position, Velocity)>() {
+        for (entity, (mut pos, vel)) in world.iter_entities::<(&mut Position, &mut Velocity)>() {
             pos.0 += vel.0;
             pos.1 += vel.1;
             assert!(velocity_entities.contains(&entity));
@@ -367,7 +648,7 @@ mod tests {
 
         world.remove_component::<Position>(e1).unwrap();
 
-        for (mut pos,) in world.iter::<(Position,)>() {
+        for (mut pos,) in world.iter::<(&mut Position,)>() {
             pos.0 = 5;
         }
 
@@ -379,4 +660,164 @@ mod tests {
         assert_eq!(e3.index, e4.index);
         assert_ne!(e3, e4);
     }
+
+    #[test]
+    fn resources() {
+        struct DeltaTime(f32);
+
+        let mut world = World::new();
+
+        assert!(world.resource::<DeltaTime>().is_none());
+
+        world.insert_resource(DeltaTime(0.016));
+        assert_eq!(world.resource::<DeltaTime>().unwrap().0, 0.016);
+
+        world.resource_mut::<DeltaTime>().unwrap().0 = 0.032;
+        assert_eq!(world.resource::<DeltaTime>().unwrap().0, 0.032);
+
+        world.insert_resource(DeltaTime(0.064));
+        assert_eq!(world.resource::<DeltaTime>().unwrap().0, 0.064);
+    }
+
+    #[test]
+    fn try_locked() {
+        let mut world = World::new();
+
+        world.register_component::<Position>();
+
+        let e1 = world.add_entity().insert(Position(0, 0)).unwrap().entity();
+
+        {
+            let _pos = world.try_get_component_mut::<Position>(e1).unwrap();
+            assert_eq!(
+                world.try_get_component_mut::<Position>(e1).unwrap_err(),
+                Error::ComponentLocked(TypeId::of::<Position>())
+            );
+            assert!(world.try_iter::<(&mut Position,)>().is_err());
+        }
+
+        // the borrow above is released, so this now succeeds
+        assert!(world.try_get_component_mut::<Position>(e1).is_ok());
+        assert!(world.try_iter::<(&mut Position,)>().is_ok());
+    }
+
+    #[test]
+    fn query_filters() {
+        let mut world = World::new();
+
+        world.register_component::<Position>();
+        world.register_component::<Velocity>();
+
+        let e1 = world
+            .add_entity()
+            .insert(Position(0, 0))
+            .unwrap()
+            .insert(Velocity(5, 5))
+            .unwrap()
+            .entity();
+        let e2 = world.add_entity().insert(Position(0, 0)).unwrap().entity();
+
+        let without_velocity: Vec<_> = world
+            .iter_entities::<(&mut Position, Without<Velocity>)>()
+            .map(|(e, _)| e)
+            .collect();
+        assert_eq!(without_velocity, vec![e2]);
+
+        let mut with_optional_velocity: Vec<_> = world
+            .iter_entities::<(&mut Position, Opt<Velocity>)>()
+            .map(|(e, (_, vel))| (e, vel.is_some()))
+            .collect();
+        with_optional_velocity.sort_by_key(|(e, _)| e.index);
+        assert_eq!(with_optional_velocity, vec![(e1, true), (e2, false)]);
+    }
+
+    #[test]
+    fn query_with_no_required_terms_excludes_dead_entities() {
+        let mut world = World::new();
+
+        world.register_component::<Position>();
+        world.register_component::<Velocity>();
+
+        let e1 = world.add_entity().insert(Position(1, 1)).unwrap().entity();
+        let e2 = world.add_entity().insert(Position(2, 2)).unwrap().entity();
+        let e3 = world.add_entity().insert(Position(3, 3)).unwrap().entity();
+        world.remove_entity(e2);
+
+        // (Opt<Position>, Without<Velocity>) has an empty required mask, since neither term is
+        // required; it must still skip e2's now-dead slot rather than matching every index.
+        let mut entities: Vec<_> = world
+            .iter_entities::<(Opt<Position>, Without<Velocity>)>()
+            .map(|(e, _)| e)
+            .collect();
+        entities.sort_by_key(|e| e.index);
+        assert_eq!(entities, vec![e1, e3]);
+    }
+
+    #[test]
+    fn component_hooks() {
+        let mut world = World::new();
+
+        let log = Rc::new(RefCell::new(Vec::new()));
+
+        let insert_log = log.clone();
+        let remove_log = log.clone();
+        world.register_component_with_hooks(Hooks {
+            on_insert: Some(Box::new(move |e, pos: &Position| {
+                insert_log.borrow_mut().push((e, "insert", pos.0));
+            })),
+            on_remove: Some(Box::new(move |e, pos: &Position| {
+                remove_log.borrow_mut().push((e, "remove", pos.0));
+            })),
+        });
+
+        let e1 = world.add_entity().insert(Position(1, 1)).unwrap().entity();
+        world.entity(e1).unwrap().remove::<Position>().unwrap();
+
+        assert_eq!(
+            *log.borrow(),
+            vec![(e1, "insert", 1), (e1, "remove", 1)]
+        );
+
+        log.borrow_mut().clear();
+        let e2 = world.add_entity().insert(Position(2, 2)).unwrap().entity();
+        world.remove_entity(e2);
+
+        assert_eq!(*log.borrow(), vec![(e2, "insert", 2), (e2, "remove", 2)]);
+    }
+
+    #[test]
+    fn batch_entity_access() {
+        let mut world = World::new();
+
+        world.register_component::<Position>();
+
+        let e1 = world.add_entity().insert(Position(1, 1)).unwrap().entity();
+        let e2 = world.add_entity().insert(Position(2, 2)).unwrap().entity();
+        world.remove_entity(e2);
+
+        let [entry1] = world.entities([e1]).unwrap();
+        assert_eq!(entry1.entity(), e1);
+
+        assert_eq!(
+            world.entities(e2).unwrap_err(),
+            Error::DeadEntityAccess(e2)
+        );
+        assert_eq!(
+            world.entities([e1, e2]).unwrap_err(),
+            Error::DeadEntityAccess(e2)
+        );
+
+        let e3 = world.add_entity().insert(Position(3, 3)).unwrap().entity();
+        let ids = [e1, e3];
+        let entries = world.entities(&ids[..]).unwrap();
+        assert_eq!(
+            entries.iter().map(|e| e.entity()).collect::<Vec<_>>(),
+            vec![e1, e3]
+        );
+
+        let positions = world.get_components::<Position>(&[e1, e2, e3]);
+        assert_eq!(positions[0].as_ref().map(|p| p.0), Some(1));
+        assert!(positions[1].is_none());
+        assert_eq!(positions[2].as_ref().map(|p| p.0), Some(3));
+    }
 }